@@ -1,21 +1,42 @@
-use std::collections::HashSet;
-use std::net::{Ipv4Addr, ToSocketAddrs};
-use std::process::Command;
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr, UdpSocket};
 use std::str::FromStr;
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
 use std::thread;
 use std::time::Duration;
 
-use clap::Parser;
+use clap::{Parser, ValueEnum};
 use etherparse::SlicedPacket;
 use log::{error, info, trace, warn};
 use pcap::{Capture, Device};
 use trust_dns_proto::op::{Message, MessageType};
 use trust_dns_proto::rr::{Name, RData, RecordType};
 
+use crate::resolver::Resolver;
+use crate::route::RouteManager;
 use crate::store::{init_dns_log_store, LogEntry};
 
+mod resolver;
+mod resolvconf;
+mod route;
 mod store;
 
+/// Shared handle to the DNS log store; cloned into the background clean-up thread.
+type SharedStore = Arc<Mutex<Box<dyn store::DnsLogStore + Send>>>;
+
+/// How often the background thread evicts expired entries from the store.
+const CLEAN_UP_INTERVAL: Duration = Duration::from_secs(3600);
+
+/// Operating mode used to observe DNS responses.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+enum Mode {
+    /// Passively sniff DNS traffic with promiscuous pcap capture.
+    Pcap,
+    /// Act as a forwarding DNS proxy on local UDP port 53.
+    Proxy,
+}
+
 #[derive(Parser)]
 #[clap(author, version, about, long_about = None)]
 struct Args {
@@ -29,46 +50,84 @@ struct Args {
     /// Path of database to store recent DNS query history
     #[clap(short = 'd', long, value_parser, value_hint = clap::ValueHint::FilePath)]
     db_path: Option<String>,
+    /// Upstream nameserver used to actively re-query logged hosts on startup
+    #[clap(short = 'u', long)]
+    upstream: Option<String>,
+    /// Capture mode: passive pcap sniffing (default) or a forwarding DNS proxy
+    #[clap(short = 'm', long, value_enum, default_value_t = Mode::Pcap)]
+    mode: Mode,
+    /// Upstream forwarders for proxy mode, tried round-robin (e.g. 1.1.1.1 8.8.8.8)
+    #[clap(short = 'f', long)]
+    forwarders: Vec<String>,
 }
 
-struct DnsAutoRoutes<'a> {
-    target: Ipv4Addr,
-    alias: HashSet<Name>,
+struct DnsAutoRoutes {
+    target: IpAddr,
+    /// Maps a CNAME alias to the originating corp host that resolved to it, so
+    /// the terminal A/AAAA record (whose owner is the alias, not a corp zone)
+    /// is still attributed to and logged under the corp host.
+    alias: HashMap<Name, Name>,
     corp_zones: Vec<Name>,
     net_if: Option<String>,
-    store: Option<Box<dyn store::DnsLogStore + 'a>>,
+    upstream: Option<SocketAddr>,
+    mode: Mode,
+    forwarders: Vec<SocketAddr>,
+    nameservers: Vec<IpAddr>,
+    routes: RouteManager,
+    store: Option<SharedStore>,
 }
 
-fn replay_logged_entries(entries: Vec<LogEntry>) {
-    thread::spawn(|| {
-        thread::sleep(Duration::from_secs(1)); // delay 1 sec
-        for entry in entries {
-            let addr_port = format!("{}:80", entry.host);
-            match addr_port.to_socket_addrs() {
-                Ok(_) => info!("Resolving logged entry {}", entry.host),
-                Err(e) => warn!("failed to resolving logged entry {}: {}", entry.host, e),
-            }
-        }
-    });
+/// Parse a nameserver argument such as `1.1.1.1` or `1.1.1.1:53` into a socket
+/// address, defaulting to port 53 when no port is given.
+fn parse_nameserver(s: &str) -> SocketAddr {
+    SocketAddr::from_str(s)
+        .or_else(|_| IpAddr::from_str(s).map(|ip| SocketAddr::new(ip, 53)))
+        .unwrap_or_else(|_| panic!("invalid upstream nameserver: {}", s))
 }
 
-impl<'a> DnsAutoRoutes<'a> {
+impl DnsAutoRoutes {
     pub fn new(args: &Args) -> DnsAutoRoutes {
-        let target = Ipv4Addr::from_str(args.target.as_str()).unwrap();
-        let corp_zones = args
+        let target = IpAddr::from_str(args.target.as_str()).unwrap();
+        let resolv = resolvconf::parse();
+        let mut corp_zones: Vec<Name> = args
             .domain_suffices
             .iter()
             .filter_map(|s| Name::from_utf8(s).ok())
             .collect();
+        // Auto-populate default domain suffixes from resolv.conf search/domain.
+        for suffix in &resolv.search {
+            if let Ok(name) = Name::from_utf8(suffix) {
+                if !corp_zones.contains(&name) {
+                    corp_zones.push(name);
+                }
+            }
+        }
         let store = match &args.db_path {
             None => None,
-            Some(path) => Some(init_dns_log_store(path).unwrap()),
+            Some(path) => Some(Arc::new(Mutex::new(init_dns_log_store(path).unwrap()))),
+        };
+        let nameservers = resolv.nameservers;
+        // Fall back to the resolv.conf nameservers when no upstream is given.
+        let upstream = args
+            .upstream
+            .as_ref()
+            .map(|s| parse_nameserver(s))
+            .or_else(|| nameservers.first().map(|ip| SocketAddr::new(*ip, 53)));
+        let forwarders = if args.forwarders.is_empty() {
+            nameservers.iter().map(|ip| SocketAddr::new(*ip, 53)).collect()
+        } else {
+            args.forwarders.iter().map(|s| parse_nameserver(s)).collect()
         };
         DnsAutoRoutes {
             target,
             corp_zones,
-            alias: HashSet::new(),
+            alias: HashMap::new(),
             net_if: args.net_if.clone(),
+            upstream,
+            mode: args.mode,
+            forwarders,
+            nameservers,
+            routes: RouteManager::new(target),
             store,
         }
     }
@@ -83,6 +142,23 @@ impl<'a> DnsAutoRoutes<'a> {
                 .join(", ")
         );
         info!("Target IP: {}", self.target);
+        self.routes.install_signal_handler();
+        self.spawn_clean_up();
+        let logged_entries = self.load_logged_entries();
+        match logged_entries {
+            None => info!("No logged entries loaded from DB."),
+            Some(e) => {
+                info!("{} logged entries loaded from DB.", e.len());
+                self.replay_logged_entries(e);
+            }
+        }
+        match self.mode {
+            Mode::Pcap => self.start_pcap(),
+            Mode::Proxy => self.start_proxy(),
+        }
+    }
+
+    fn start_pcap(&mut self) {
         let device = match &self.net_if {
             Some(if_name) => Device::from(if_name.as_str()),
             _ => Device::lookup().unwrap(),
@@ -94,15 +170,28 @@ impl<'a> DnsAutoRoutes<'a> {
             .immediate_mode(true)
             .open()
             .unwrap();
-        let logged_entries = self.load_logged_entries();
-        match logged_entries {
-            None => info!("No logged entries loaded from DB."),
-            Some(e) => {
-                info!("{} logged entries loaded from DB.", e.len());
-                replay_logged_entries(e)
-            }
-        }
-        cap.filter("udp port 53", true).unwrap();
+        // Restrict capture to the configured nameservers (discovered from
+        // resolv.conf) when we have them and no interface was given with
+        // `--net-if`, to cut out unrelated port-53 noise.
+        // Loopback/stub resolvers (e.g. systemd-resolved's 127.0.0.53) never
+        // appear on the captured device, so filtering on them would capture
+        // nothing; skip them and fall back to plain `udp port 53`.
+        let hosts: Vec<String> = if self.net_if.is_some() {
+            Vec::new()
+        } else {
+            self.nameservers
+                .iter()
+                .filter(|ip| !ip.is_loopback())
+                .map(|ip| format!("host {}", ip))
+                .collect()
+        };
+        let filter = if hosts.is_empty() {
+            "udp port 53".to_string()
+        } else {
+            format!("udp port 53 and ({})", hosts.join(" or "))
+        };
+        info!("BPF filter: {}", filter);
+        cap.filter(&filter, true).unwrap();
         while let Ok(packet) = cap.next() {
             match SlicedPacket::from_ethernet(packet.data) {
                 Err(value) => println!("Err {:?}", value),
@@ -118,42 +207,137 @@ impl<'a> DnsAutoRoutes<'a> {
         }
     }
 
-    fn load_logged_entries(&self) -> Option<Vec<LogEntry>> {
-        if self.store.is_none() {
-            return None;
+    /// Forwarding DNS-proxy mode: serve queries on local UDP/53, relay each to an
+    /// upstream forwarder (round-robin), route the answer, then return the
+    /// unmodified upstream bytes to the client.
+    fn start_proxy(&mut self) {
+        if self.forwarders.is_empty() {
+            error!("Proxy mode requires at least one --forwarders entry.");
+            return;
         }
-        let store = self.store.as_ref().unwrap();
-        let entries = store.load_entries();
+        let socket = match UdpSocket::bind("0.0.0.0:53") {
+            Ok(s) => s,
+            Err(e) => {
+                error!("Failed to bind UDP 53 for proxy mode: {}", e);
+                return;
+            }
+        };
+        info!("Proxying DNS on 0.0.0.0:53 via {:?}", self.forwarders);
+        // Each datagram is forwarded on its own thread so a slow/unreachable
+        // forwarder (up to QUERY_TIMEOUT) cannot stall other clients. Workers
+        // hand the response to the main thread, which routes them serially
+        // (keeping `log_dns_response` single threaded) and only then relays the
+        // answer back to the client.
+        let (tx, rx) = mpsc::channel::<(Vec<u8>, SocketAddr)>();
+        let accept_socket = match socket.try_clone() {
+            Ok(s) => s,
+            Err(e) => {
+                error!("Failed to clone proxy socket: {}", e);
+                return;
+            }
+        };
+        let forwarders = self.forwarders.clone();
+        thread::spawn(move || {
+            let mut buf = [0u8; 4096];
+            let mut next_forwarder = 0usize;
+            loop {
+                let (len, client) = match accept_socket.recv_from(&mut buf) {
+                    Ok(v) => v,
+                    Err(e) => {
+                        warn!("proxy recv error: {}", e);
+                        continue;
+                    }
+                };
+                let query = buf[..len].to_vec();
+                let id = match Message::from_vec(&query) {
+                    Ok(m) => m.id(),
+                    Err(e) => {
+                        warn!("dropping malformed query from {}: {}", client, e);
+                        continue;
+                    }
+                };
+                let forwarder = forwarders[next_forwarder % forwarders.len()];
+                next_forwarder = next_forwarder.wrapping_add(1);
+                let tx = tx.clone();
+                thread::spawn(move || match resolver::forward(forwarder, &query, id) {
+                    Some(response) => {
+                        let _ = tx.send((response, client));
+                    }
+                    None => warn!("no response from {} for query from {}", forwarder, client),
+                });
+            }
+        });
+        for (response, client) in rx {
+            if let Ok(msg) = Message::from_vec(&response) {
+                if msg.header().message_type() == MessageType::Response {
+                    self.log_dns_response(&msg);
+                }
+            }
+            if let Err(e) = socket.send_to(&response, client) {
+                warn!("failed to return response to {}: {}", client, e);
+            }
+        }
+    }
+
+    /// Warm up routes for previously seen hosts by actively re-querying each one
+    /// against the configured upstream and feeding the answers back through the
+    /// same routing path as sniffed responses.
+    fn replay_logged_entries(&mut self, entries: Vec<LogEntry>) {
+        let upstream = match self.upstream {
+            Some(u) => u,
+            None => {
+                warn!("No upstream configured; skipping active replay of logged entries.");
+                return;
+            }
+        };
+        let mut resolver = match Resolver::new(upstream) {
+            Ok(r) => r,
+            Err(e) => {
+                warn!("failed to create resolver: {}", e);
+                return;
+            }
+        };
+        let hosts: Vec<String> = entries.into_iter().map(|e| e.host).collect();
+        info!("Replaying {} logged hosts via {}", hosts.len(), upstream);
+        for msg in resolver.resolve(&hosts) {
+            self.log_dns_response(&msg);
+        }
+    }
+
+    fn load_logged_entries(&self) -> Option<Vec<LogEntry>> {
+        let store = self.store.as_ref()?;
+        let entries = store.lock().unwrap().load_entries();
         match entries {
             Ok(e) => Some(e),
             Err(_) => None,
         }
     }
 
-    fn add_vpn_route(&self, ip: &str) {
-        match Command::new("route")
-            .arg("-n")
-            .arg("add")
-            .arg("-host")
-            .arg(ip)
-            .arg(self.target.to_string())
-            .output()
-        {
-            Ok(output) => {
-                if !output.status.success() {
-                    warn!("Failed to add route: {:?}", output.stderr);
-                }
+    fn spawn_clean_up(&self) {
+        let store = self.store.clone();
+        let routes = self.routes.clone();
+        thread::spawn(move || loop {
+            thread::sleep(CLEAN_UP_INTERVAL);
+            if let Some(store) = &store {
+                store.lock().unwrap().clean_up();
             }
-            Err(e) => error!("Failed to add route: {:?}", e),
-        }
+            routes.reap_expired();
+        });
     }
 
-    fn on_query_corp(&mut self, host: &str) {
+    fn add_vpn_route(&self, ip: &IpAddr, ttl: u32) {
+        self.routes.add(*ip, ttl);
+    }
+
+    fn on_query_corp(&mut self, host: &str, addr: &str, ttl: u32) {
         if self.store.is_none() {
             return;
         }
-        let store = self.store.as_mut().unwrap();
-        let r = store.on_query(host.trim_end_matches("."));
+        let store = self.store.as_ref().unwrap();
+        let r = store
+            .lock()
+            .unwrap()
+            .on_query(host.trim_end_matches("."), addr, ttl);
         match r {
             Ok(_) => {}
             Err(_) => warn!("Failed to log dns entry in store: {}", host),
@@ -163,27 +347,41 @@ impl<'a> DnsAutoRoutes<'a> {
     fn log_dns_response(&mut self, msg: &Message) {
         for ans in msg.answers() {
             let mut is_corp = false;
-            let is_alias = self.alias.contains(&ans.name());
             for zone in self.corp_zones.iter() {
                 if zone.zone_of(ans.name()) {
                     is_corp = true;
                     break;
                 }
             }
-            if is_corp {
-                self.on_query_corp(&ans.name().to_string())
-            }
+            // The corp host this answer belongs to: the owner name itself for a
+            // direct corp match, otherwise the corp host that CNAME'd to it.
+            let corp_name = if is_corp {
+                Some(ans.name().clone())
+            } else {
+                self.alias.get(&ans.name()).cloned()
+            };
             match (ans.rr_type(), ans.data()) {
                 (RecordType::A, Some(RData::A(addr))) => {
                     trace!("Answer: {} {} {}", ans.name(), ans.rr_type(), addr);
-                    if is_corp || is_alias {
-                        self.add_vpn_route(&addr.to_string());
+                    if let Some(corp_name) = &corp_name {
+                        let ip = IpAddr::V4(*addr);
+                        self.on_query_corp(&corp_name.to_string(), &ip.to_string(), ans.ttl());
+                        self.add_vpn_route(&ip, ans.ttl());
+                    }
+                }
+                (RecordType::AAAA, Some(RData::AAAA(addr))) => {
+                    trace!("Answer: {} {} {}", ans.name(), ans.rr_type(), addr);
+                    if let Some(corp_name) = &corp_name {
+                        let ip = IpAddr::V6(*addr);
+                        self.on_query_corp(&corp_name.to_string(), &ip.to_string(), ans.ttl());
+                        self.add_vpn_route(&ip, ans.ttl());
                     }
                 }
                 (RecordType::CNAME, Some(RData::CNAME(cname))) => {
                     trace!("Answer: {} {} {}", ans.name(), ans.rr_type(), cname);
-                    if is_corp {
-                        let _ = &self.alias.insert(cname.clone());
+                    // Carry the corp attribution forward across the CNAME chain.
+                    if let Some(corp_name) = corp_name {
+                        self.alias.insert(cname.clone(), corp_name);
                     }
                 }
                 _ => {}
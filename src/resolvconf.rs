@@ -0,0 +1,45 @@
+use std::fs;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+/// Subset of `/etc/resolv.conf` relevant to bootstrapping the client config:
+/// the configured nameservers and the search/domain suffixes.
+pub struct ResolvConf {
+    pub nameservers: Vec<IpAddr>,
+    pub search: Vec<String>,
+}
+
+/// Parse the system `/etc/resolv.conf`.
+pub fn parse() -> ResolvConf {
+    parse_path("/etc/resolv.conf")
+}
+
+fn parse_path(path: &str) -> ResolvConf {
+    let mut nameservers = Vec::new();
+    let mut search = Vec::new();
+    let contents = fs::read_to_string(path).unwrap_or_default();
+    for line in contents.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+        let mut parts = line.split_whitespace();
+        match parts.next() {
+            Some("nameserver") => {
+                if let Some(addr) = parts.next().and_then(|s| IpAddr::from_str(s).ok()) {
+                    nameservers.push(addr);
+                }
+            }
+            // `domain` names a single suffix, `search` a list; both feed the
+            // default domain suffixes. A later line overrides earlier ones.
+            Some("search") | Some("domain") => {
+                search = parts.map(|s| s.to_string()).collect();
+            }
+            _ => {}
+        }
+    }
+    ResolvConf {
+        nameservers,
+        search,
+    }
+}
@@ -0,0 +1,194 @@
+use std::collections::HashMap;
+use std::io::ErrorKind;
+use std::net::{SocketAddr, UdpSocket};
+use std::time::{Duration, Instant};
+
+use log::{trace, warn};
+use trust_dns_proto::op::{Message, MessageType, OpCode, Query};
+use trust_dns_proto::rr::{Name, RecordType};
+
+/// Initial retransmit delay for an outstanding query.
+const INITIAL_RETRANSMIT: Duration = Duration::from_millis(1000);
+/// Upper bound on the (doubling) retransmit delay.
+const MAX_RETRANSMIT: Duration = Duration::from_secs(10);
+/// Overall deadline after which a query is abandoned.
+const QUERY_TIMEOUT: Duration = Duration::from_secs(10);
+/// Record types we actively re-query for each logged host.
+const QUERY_TYPES: [RecordType; 2] = [RecordType::A, RecordType::AAAA];
+
+/// A query awaiting a response, keyed by its 16-bit DNS transaction ID.
+struct Outstanding {
+    host: String,
+    record_type: RecordType,
+    sent_at: Instant,
+    next_retransmit: Instant,
+    retransmit_delay: Duration,
+    attempts: u32,
+}
+
+/// Actively re-queries each host against `upstream` over UDP/53, driving its own
+/// retransmit/backoff loop instead of relying on the blocking system resolver.
+/// Returns the raw response messages so the caller can route them exactly like
+/// sniffed traffic.
+pub struct Resolver {
+    upstream: SocketAddr,
+    socket: UdpSocket,
+    next_id: u16,
+}
+
+/// Forward a raw query to `upstream` and return the raw response bytes, driving
+/// the same retransmit/backoff schedule as active queries. Returns `None` if the
+/// query is abandoned after the overall timeout. Used by the forwarding proxy.
+pub fn forward(upstream: SocketAddr, query: &[u8], id: u16) -> Option<Vec<u8>> {
+    let socket = match UdpSocket::bind("0.0.0.0:0") {
+        Ok(s) => s,
+        Err(e) => {
+            warn!("failed to bind forwarder socket: {}", e);
+            return None;
+        }
+    };
+    let _ = socket.set_read_timeout(Some(Duration::from_millis(200)));
+    let sent_at = Instant::now();
+    let mut next_retransmit = sent_at + INITIAL_RETRANSMIT;
+    let mut retransmit_delay = INITIAL_RETRANSMIT;
+    if socket.send_to(query, upstream).is_err() {
+        return None;
+    }
+    let mut buf = [0u8; 4096];
+    loop {
+        match socket.recv_from(&mut buf) {
+            Ok((len, _)) => {
+                if let Ok(msg) = Message::from_vec(&buf[..len]) {
+                    if msg.id() == id {
+                        return Some(buf[..len].to_vec());
+                    }
+                }
+            }
+            Err(e) if e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::TimedOut => {}
+            Err(e) => {
+                warn!("forwarder socket error: {}", e);
+                return None;
+            }
+        }
+        let now = Instant::now();
+        if now.duration_since(sent_at) >= QUERY_TIMEOUT {
+            return None;
+        }
+        if now >= next_retransmit {
+            let _ = socket.send_to(query, upstream);
+            retransmit_delay = (retransmit_delay * 2).min(MAX_RETRANSMIT);
+            next_retransmit = now + retransmit_delay;
+        }
+    }
+}
+
+impl Resolver {
+    pub fn new(upstream: SocketAddr) -> std::io::Result<Resolver> {
+        let socket = UdpSocket::bind("0.0.0.0:0")?;
+        socket.set_read_timeout(Some(Duration::from_millis(200)))?;
+        Ok(Resolver {
+            upstream,
+            socket,
+            next_id: 1,
+        })
+    }
+
+    fn alloc_id(&mut self) -> u16 {
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1);
+        id
+    }
+
+    fn send_query(&self, id: u16, host: &str, record_type: RecordType) -> std::io::Result<()> {
+        let name = match Name::from_utf8(host) {
+            Ok(n) => n,
+            Err(e) => {
+                warn!("skipping invalid host {}: {}", host, e);
+                return Ok(());
+            }
+        };
+        let mut msg = Message::new();
+        msg.set_id(id)
+            .set_message_type(MessageType::Query)
+            .set_op_code(OpCode::Query)
+            .set_recursion_desired(true);
+        msg.add_query(Query::query(name, record_type));
+        let bytes = msg
+            .to_vec()
+            .map_err(|e| std::io::Error::new(ErrorKind::InvalidData, e))?;
+        self.socket.send_to(&bytes, self.upstream)?;
+        Ok(())
+    }
+
+    /// Re-query every host for A/AAAA and collect the upstream responses.
+    pub fn resolve(&mut self, hosts: &[String]) -> Vec<Message> {
+        let mut pending: HashMap<u16, Outstanding> = HashMap::new();
+        let now = Instant::now();
+        for host in hosts {
+            for &record_type in QUERY_TYPES.iter() {
+                let id = self.alloc_id();
+                if self.send_query(id, host, record_type).is_err() {
+                    continue;
+                }
+                pending.insert(
+                    id,
+                    Outstanding {
+                        host: host.clone(),
+                        record_type,
+                        sent_at: now,
+                        next_retransmit: now + INITIAL_RETRANSMIT,
+                        retransmit_delay: INITIAL_RETRANSMIT,
+                        attempts: 1,
+                    },
+                );
+            }
+        }
+
+        let mut responses = Vec::new();
+        let mut buf = [0u8; 4096];
+        while !pending.is_empty() {
+            match self.socket.recv_from(&mut buf) {
+                Ok((len, _)) => {
+                    if let Ok(msg) = Message::from_vec(&buf[..len]) {
+                        if pending.remove(&msg.id()).is_some() {
+                            responses.push(msg);
+                        }
+                    }
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::TimedOut => {}
+                Err(e) => {
+                    warn!("resolver socket error: {}", e);
+                    break;
+                }
+            }
+            self.service_timeouts(&mut pending);
+        }
+        responses
+    }
+
+    /// Retransmit or abandon queries whose timers have elapsed.
+    fn service_timeouts(&self, pending: &mut HashMap<u16, Outstanding>) {
+        let now = Instant::now();
+        let mut expired = Vec::new();
+        for (&id, q) in pending.iter_mut() {
+            if now.duration_since(q.sent_at) >= QUERY_TIMEOUT {
+                warn!(
+                    "abandoning {} {} query after {} attempts",
+                    q.host, q.record_type, q.attempts
+                );
+                expired.push(id);
+                continue;
+            }
+            if now >= q.next_retransmit {
+                trace!("retransmitting {} {} (attempt {})", q.host, q.record_type, q.attempts + 1);
+                let _ = self.send_query(id, &q.host, q.record_type);
+                q.attempts += 1;
+                q.retransmit_delay = (q.retransmit_delay * 2).min(MAX_RETRANSMIT);
+                q.next_retransmit = now + q.retransmit_delay;
+            }
+        }
+        for id in expired {
+            pending.remove(&id);
+        }
+    }
+}
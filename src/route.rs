@@ -0,0 +1,154 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::process::Command;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use log::{error, info, warn};
+
+/// Grace lifetime applied to routes whose answer carried `ttl == 0` (legitimate
+/// for many load balancers), matching the store's legacy-row grace window so
+/// route and store lifetimes stay consistent.
+const ZERO_TTL_GRACE: u64 = 86400 * 7;
+
+/// A host route installed into the kernel routing table.
+struct Route {
+    inserted_at: u64,
+    ttl: u32,
+}
+
+impl Route {
+    /// Unix timestamp at which this route becomes eligible for reaping.
+    fn expires_at(&self) -> u64 {
+        let ttl = if self.ttl == 0 {
+            ZERO_TTL_GRACE
+        } else {
+            self.ttl as u64
+        };
+        self.inserted_at + ttl
+    }
+}
+
+/// Tracks the host routes this process installs so they can be de-duplicated,
+/// reaped once their TTL elapses, and removed again on shutdown instead of
+/// leaking kernel routing-table entries.
+#[derive(Clone)]
+pub struct RouteManager {
+    target: IpAddr,
+    routes: Arc<Mutex<HashMap<IpAddr, Route>>>,
+}
+
+impl RouteManager {
+    pub fn new(target: IpAddr) -> RouteManager {
+        RouteManager {
+            target,
+            routes: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Install a signal handler that removes every tracked route on Ctrl-C
+    /// before exiting, so routes do not survive the process.
+    pub fn install_signal_handler(&self) {
+        let manager = self.clone();
+        let result = ctrlc::set_handler(move || {
+            info!("Shutting down, removing installed routes.");
+            manager.remove_all();
+            std::process::exit(0);
+        });
+        if let Err(e) = result {
+            warn!("Failed to install signal handler: {}", e);
+        }
+    }
+
+    /// Install a host route for `ip` via the target, de-duplicating so the same
+    /// IP is not re-added on every repeated DNS answer.
+    pub fn add(&self, ip: IpAddr, ttl: u32) {
+        let mut routes = self.routes.lock().unwrap();
+        // Repeated answer for a live host: refresh its expiry instead of letting
+        // the route reap at its original insertion time and flap.
+        if let Some(route) = routes.get_mut(&ip) {
+            route.inserted_at = now();
+            route.ttl = ttl;
+            return;
+        }
+        if self.install(&ip) {
+            routes.insert(
+                ip,
+                Route {
+                    inserted_at: now(),
+                    ttl,
+                },
+            );
+        }
+    }
+
+    /// Remove routes whose TTL has elapsed. Called alongside `store.clean_up()`.
+    pub fn reap_expired(&self) {
+        let now = now();
+        let mut routes = self.routes.lock().unwrap();
+        let expired: Vec<IpAddr> = routes
+            .iter()
+            .filter(|(_, r)| r.expires_at() < now)
+            .map(|(ip, _)| *ip)
+            .collect();
+        for ip in expired {
+            self.delete(&ip);
+            routes.remove(&ip);
+        }
+    }
+
+    fn remove_all(&self) {
+        let mut routes = self.routes.lock().unwrap();
+        for ip in routes.keys() {
+            self.delete(ip);
+        }
+        routes.clear();
+    }
+
+    fn install(&self, ip: &IpAddr) -> bool {
+        let mut cmd = Command::new("route");
+        cmd.arg("-n").arg("add");
+        // IPv6 host routes need the address family explicitly selected.
+        if ip.is_ipv6() {
+            cmd.arg("-inet6");
+        }
+        cmd.arg("-host").arg(ip.to_string()).arg(self.target.to_string());
+        match cmd.output() {
+            Ok(output) => {
+                if !output.status.success() {
+                    warn!("Failed to add route: {:?}", output.stderr);
+                    return false;
+                }
+                true
+            }
+            Err(e) => {
+                error!("Failed to add route: {:?}", e);
+                false
+            }
+        }
+    }
+
+    fn delete(&self, ip: &IpAddr) {
+        let mut cmd = Command::new("route");
+        cmd.arg("-n").arg("delete");
+        if ip.is_ipv6() {
+            cmd.arg("-inet6");
+        }
+        cmd.arg("-host").arg(ip.to_string());
+        match cmd.output() {
+            Ok(output) => {
+                if !output.status.success() {
+                    warn!("Failed to delete route: {:?}", output.stderr);
+                }
+            }
+            Err(e) => error!("Failed to delete route: {:?}", e),
+        }
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
@@ -1,10 +1,13 @@
-use std::ops::Sub;
-use std::time::{Duration, SystemTime, SystemTimeError, UNIX_EPOCH};
+use std::time::{SystemTime, SystemTimeError, UNIX_EPOCH};
 
 use sqlite::Connection;
 
 pub type Result<T> = std::result::Result<T, StoreError>;
 
+/// Grace TTL applied to legacy rows that predate the `ttl` column (stored as 0),
+/// so upgrading does not instantly expire the whole history.
+const LEGACY_TTL: u32 = 86400 * 7;
+
 #[derive(Debug, Clone)]
 pub struct StoreError {
     message: String,
@@ -13,11 +16,13 @@ pub struct StoreError {
 pub struct LogEntry {
     pub timestamp: u64,
     pub host: String,
+    pub addr: String,
+    pub ttl: u32,
 }
 
 pub trait DnsLogStore {
     fn clean_up(&mut self);
-    fn on_query(&mut self, host: &str) -> Result<()>;
+    fn on_query(&mut self, host: &str, addr: &str, ttl: u32) -> Result<()>;
     fn load_entries(&self) -> Result<Vec<LogEntry>>;
 }
 
@@ -25,7 +30,7 @@ struct SQLiteDnsLogStore {
     conn: Connection,
 }
 
-pub fn init_dns_log_store(path: &str) -> Result<Box<dyn DnsLogStore>> {
+pub fn init_dns_log_store(path: &str) -> Result<Box<dyn DnsLogStore + Send>> {
     let conn = sqlite::open(path)?;
     let mut store = SQLiteDnsLogStore { conn };
     store.init_db()?;
@@ -39,49 +44,81 @@ impl SQLiteDnsLogStore {
             CREATE UNIQUE INDEX IF NOT EXISTS uniq_host ON dns_log (host);
             ",
         )?;
+        // Older databases only carried (timestamp, host); add the columns that
+        // back per-record TTL expiry if they are not present yet.
+        self.add_column_if_missing("addr", "TEXT NOT NULL DEFAULT ''")?;
+        self.add_column_if_missing("ttl", "INTEGER NOT NULL DEFAULT 0")?;
+        Ok(())
+    }
+
+    fn add_column_if_missing(&mut self, column: &str, decl: &str) -> Result<()> {
+        let mut present = false;
+        let statement = self.conn.prepare("PRAGMA table_info(dns_log)")?;
+        let mut cursor = statement.into_cursor();
+        while let Some(row) = cursor.next()? {
+            if row[1].as_string() == Some(column) {
+                present = true;
+                break;
+            }
+        }
+        if !present {
+            self.conn.execute(format!(
+                "ALTER TABLE dns_log ADD COLUMN {} {};",
+                column, decl
+            ))?;
+        }
         Ok(())
     }
 }
 
 impl DnsLogStore for SQLiteDnsLogStore {
     fn clean_up(&mut self) {
-        todo!()
+        // Evict rows whose record TTL has elapsed, then reclaim the freed pages.
+        let sql = format!(
+            "DELETE FROM dns_log WHERE timestamp + (CASE WHEN ttl = 0 THEN {} ELSE ttl END) < CAST(strftime('%s','now') AS INTEGER); VACUUM;",
+            LEGACY_TTL
+        );
+        if let Err(e) = self.conn.execute(sql) {
+            log::warn!("Failed to clean up dns_log: {:?}", e);
+        }
     }
 
-    fn on_query(&mut self, host: &str) -> Result<()> {
+    fn on_query(&mut self, host: &str, addr: &str, ttl: u32) -> Result<()> {
         let now = SystemTime::now();
         let duration = now.duration_since(UNIX_EPOCH)?;
         let timestamp = duration.as_secs();
         let mut statement = self.conn.prepare(
-            "INSERT INTO dns_log (timestamp, host) VALUES (?, ?)\
-            ON CONFLICT(host) DO UPDATE SET timestamp=excluded.timestamp;
+            "INSERT INTO dns_log (timestamp, host, addr, ttl) VALUES (?, ?, ?, ?)\
+            ON CONFLICT(host) DO UPDATE SET timestamp=excluded.timestamp, addr=excluded.addr, ttl=excluded.ttl;
             ",
         )?;
         statement.bind(1, timestamp as i64)?;
         statement.bind(2, host)?;
+        statement.bind(3, addr)?;
+        statement.bind(4, ttl as i64)?;
         statement.next()?;
         Ok(())
     }
 
     fn load_entries(&self) -> Result<Vec<LogEntry>> {
         let mut entries: Vec<LogEntry> = Vec::new();
-        let mut statement = self
-            .conn
-            .prepare("SELECT timestamp, host FROM dns_log WHERE timestamp > ?")?;
-        let now = SystemTime::now();
-        let duration = now
-            .duration_since(UNIX_EPOCH)?
-            .sub(Duration::from_secs(86400 * 7));
-        let recent_timestamp = duration.as_secs() as i64;
-        statement.bind(1, recent_timestamp)?;
+        let mut statement = self.conn.prepare(format!(
+            "SELECT timestamp, host, addr, ttl FROM dns_log \
+            WHERE timestamp + (CASE WHEN ttl = 0 THEN {} ELSE ttl END) >= CAST(strftime('%s','now') AS INTEGER)",
+            LEGACY_TTL
+        ))?;
         let mut cursor = statement.into_cursor();
         while let Some(row) = cursor.next()? {
             let timestamp = row[0].as_integer().unwrap_or(0) as u64;
             let host = row[1].as_string().unwrap_or("");
+            let addr = row[2].as_string().unwrap_or("");
+            let ttl = row[3].as_integer().unwrap_or(0) as u32;
             if timestamp != 0 && !host.is_empty() {
                 entries.push(LogEntry {
                     timestamp,
                     host: host.to_string(),
+                    addr: addr.to_string(),
+                    ttl,
                 })
             }
         }